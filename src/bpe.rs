@@ -1,12 +1,20 @@
-use anyhow::Error;
-use pcre2::bytes::{Regex, RegexBuilder};
-use std::collections::HashMap;
+use anyhow::{anyhow, Error};
+use fancy_regex::Regex;
+use rustc_hash::FxHashMap;
+use std::collections::HashSet;
+
+// which special-token strings encode_native is allowed to recognize as tokens
+pub enum SpecialTokenAllowance {
+    None,
+    All,
+    Some(HashSet<String>),
+}
 
 pub struct CoreBpe {
-    encoder: HashMap<String, isize>,
-    decoder: HashMap<isize, String>,
-    special_tokens_encoder: HashMap<String, isize>,
-    special_tokens_decoder: HashMap<isize, String>,
+    encoder: FxHashMap<String, isize>,
+    decoder: FxHashMap<isize, String>,
+    special_tokens_encoder: FxHashMap<String, isize>,
+    special_tokens_decoder: FxHashMap<isize, String>,
     tl_regex: Regex,
     tl_special_regex: Regex,
     sorted_token_bytes: Vec<Vec<u8>>,
@@ -14,31 +22,28 @@ pub struct CoreBpe {
 
 impl CoreBpe {
     pub fn new(
-        encoder: HashMap<String, isize>,
-        special_tokens_encoder: HashMap<String, isize>,
+        encoder: FxHashMap<String, isize>,
+        special_tokens_encoder: FxHashMap<String, isize>,
         pattern: String,
     ) -> Result<Self, Error> {
         // build regex
-        let tl_regex = RegexBuilder::new()
-            .jit_if_available(true)
-            .ucp(true)
-            .utf(true)
-            .build(pattern.as_str())?;
+        let tl_regex = Regex::new(pattern.as_str())?;
         let special_regex_strings: Vec<String> = special_tokens_encoder
-            .iter()
-            .map(|token| pcre2::escape(token.0).to_string())
+            .keys()
+            .map(String::as_str)
+            .map(escape_special_token)
             .collect();
         let special_regex_pattern = special_regex_strings.join("|");
         let tl_special_regex = Regex::new(&special_regex_pattern)?;
 
         // create decoder
-        let mut decoder = HashMap::new();
+        let mut decoder = FxHashMap::default();
         for (key, value) in encoder.clone().into_iter() {
             decoder.insert(value, key);
         }
 
         // create special tokens decoder
-        let mut special_tokens_decoder = HashMap::new();
+        let mut special_tokens_decoder = FxHashMap::default();
         for (key, value) in special_tokens_encoder.clone().into_iter() {
             special_tokens_decoder.insert(value, key);
         }
@@ -59,7 +64,13 @@ impl CoreBpe {
         })
     }
 
-    pub fn encode_native(&self, text: &str) -> (Vec<isize>, usize) {
+    pub fn encode_native(
+        &self,
+        text: &str,
+        allowed_special: &SpecialTokenAllowance,
+    ) -> Result<(Vec<isize>, usize), Error> {
+        self.check_disallowed_special(text, allowed_special)?;
+
         let mut result = vec![];
         let mut last_piece_token_len = 0;
         let mut start = 0;
@@ -114,7 +125,71 @@ impl CoreBpe {
                 break;
             }
         }
-        (result, last_piece_token_len)
+        Ok((result, last_piece_token_len))
+    }
+
+    /// Returns an error identifying the offending substring and position if `text`
+    /// contains a registered special-token string that `allowed_special` does not cover.
+    fn check_disallowed_special(
+        &self,
+        text: &str,
+        allowed_special: &SpecialTokenAllowance,
+    ) -> Result<(), Error> {
+        let allowed: HashSet<&str> = match allowed_special {
+            SpecialTokenAllowance::All => {
+                self.special_tokens_encoder.keys().map(String::as_str).collect()
+            }
+            SpecialTokenAllowance::None => HashSet::new(),
+            SpecialTokenAllowance::Some(set) => set.iter().map(String::as_str).collect(),
+        };
+        let text_chars: Vec<char> = text.chars().collect();
+        for matched in find_regex_to_all_string_match_index(text, &self.tl_special_regex) {
+            let candidate = cut_chars(&text_chars, matched.0, matched.1);
+            if !allowed.contains(candidate.as_str()) {
+                return Err(anyhow!(
+                    "disallowed special token {:?} found at character position {}",
+                    candidate,
+                    matched.0
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Encodes `text` as plain text, ignoring any special-token strings it may contain.
+    pub fn encode_ordinary_native(&self, text: &str) -> Vec<isize> {
+        let mut result = vec![];
+        let text_chars: Vec<char> = text.chars().collect();
+        let store = find_regex_to_all_string_match_index(text, &self.tl_regex);
+        for matched in store {
+            let piece = cut_chars(&text_chars, matched.0, matched.1);
+            if let Some(&token) = self.encoder.get(&piece) {
+                result.push(token);
+                continue;
+            }
+            result.extend(byte_pair_encode(piece.as_bytes(), &self.encoder));
+        }
+        result
+    }
+
+    // errors on an unknown token id instead of silently dropping it
+    pub fn decode_bytes(&self, tokens: &[isize]) -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::with_capacity(tokens.len() * 2);
+        for &token in tokens {
+            if let Some(piece) = self.decoder.get(&token) {
+                bytes.extend_from_slice(piece.as_bytes());
+            } else if let Some(piece) = self.special_tokens_decoder.get(&token) {
+                bytes.extend_from_slice(piece.as_bytes());
+            } else {
+                return Err(anyhow!("unknown token id {}", token));
+            }
+        }
+        Ok(bytes)
+    }
+
+    pub fn decode_native(&self, tokens: &[isize]) -> Result<String, Error> {
+        String::from_utf8(self.decode_bytes(tokens)?)
+            .map_err(|err| anyhow!("decoded tokens are not valid utf-8: {}", err))
     }
 }
 
@@ -124,11 +199,21 @@ fn cut_chars(chars: &Vec<char>, start: usize, end: usize) -> String {
     chars[start..end].iter().collect()
 }
 
+fn escape_special_token(token: &str) -> String {
+    let mut escaped = String::with_capacity(token.len());
+    for c in token.chars() {
+        if "\\.+*?()|[]{}^$#".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
 fn find_regex_to_string_index(text: &str, regex: &Regex) -> Option<(usize, usize)> {
-    if let Some(matched) = regex.find(text.as_bytes()).unwrap() {
-        let matched_str = std::str::from_utf8(matched.as_bytes()).unwrap();
+    if let Some(matched) = regex.find(text).unwrap() {
         let start = text[..matched.start()].chars().count();
-        let end = start + matched_str.chars().count();
+        let end = start + matched.as_str().chars().count();
         Some((start, end))
     } else {
         None
@@ -137,18 +222,17 @@ fn find_regex_to_string_index(text: &str, regex: &Regex) -> Option<(usize, usize
 
 fn find_regex_to_all_string_match_index(text: &str, regex: &Regex) -> Vec<(usize, usize)> {
     regex
-        .find_iter(text.as_bytes())
+        .find_iter(text)
         .map(|matched| {
             let matched = matched.unwrap();
-            let matched_str = std::str::from_utf8(matched.as_bytes()).unwrap();
             let start = text[..matched.start()].chars().count();
-            let end = start + matched_str.chars().count();
+            let end = start + matched.as_str().chars().count();
             (start, end)
         })
         .collect()
 }
 
-fn byte_pair_encode(piece: &[u8], ranks: &HashMap<String, isize>) -> Vec<isize> {
+fn byte_pair_encode(piece: &[u8], ranks: &FxHashMap<String, isize>) -> Vec<isize> {
     if piece.len() == 1 {
         unsafe {
             let key = String::from_utf8_unchecked(Vec::from(piece));
@@ -156,56 +240,195 @@ fn byte_pair_encode(piece: &[u8], ranks: &HashMap<String, isize>) -> Vec<isize>
             return vec![value];
         }
     }
+    assert!(piece.len() > 1);
     byte_pair_merge(piece, ranks, |start, end| -> isize {
         let slice = piece[start..end].to_vec();
         unsafe {
             let key = String::from_utf8_unchecked(slice);
-            ranks.get(&key).cloned().unwrap_or(0)
+            // guaranteed present: each final slice is either a single byte or a
+            // span that byte_pair_merge only merged because `ranks` had a rank
+            // for it, so it is a token that actually exists in `ranks`.
+            *ranks.get(&key).unwrap()
         }
     })
 }
 
-fn byte_pair_merge<T, F>(piece: &[u8], ranks: &HashMap<String, isize>, f: F) -> Vec<T>
+// isize::MAX means no such pair, or no rank for it; never a real rank
+fn pair_rank(
+    piece: &[u8],
+    ranks: &FxHashMap<String, isize>,
+    parts: &[(usize, isize)],
+    i: usize,
+) -> isize {
+    if i + 3 >= parts.len() {
+        return isize::MAX;
+    }
+    let slice = &piece[parts[i].0..parts[i + 3].0];
+    unsafe {
+        let key = String::from_utf8_unchecked(slice.to_vec());
+        ranks.get(&key).copied().unwrap_or(isize::MAX)
+    }
+}
+
+// same algorithm as upstream tiktoken's byte_pair_merge
+fn byte_pair_merge<T, F>(piece: &[u8], ranks: &FxHashMap<String, isize>, f: F) -> Vec<T>
 where
     F: Fn(usize, usize) -> T,
 {
-    let mut parts: Vec<[usize; 2]> = (0..piece.len() + 1).map(|i| [i, usize::MAX]).collect();
-    let get_rank = |start_idx: usize, skip: usize, parts: &Vec<[usize; 2]>| -> isize {
-        if start_idx + skip + 2 < parts.len() {
-            let b = &piece[parts[start_idx][0]..parts[start_idx + skip + 2][0]];
-            unsafe {
-                let key = String::from_utf8_unchecked(b.to_vec());
-                if let Some(&rank) = ranks.get(&key) {
-                    return rank;
-                }
-            }
-        }
-        -1
-    };
-    for i in 0..parts.len() - 2 {
-        let rank = get_rank(i, 0, &parts);
-        if rank >= 0 {
-            parts[i][1] = rank as usize;
-        }
+    let mut parts: Vec<(usize, isize)> = Vec::with_capacity(piece.len() + 1);
+    for i in 0..piece.len() - 1 {
+        let slice = &piece[i..i + 2];
+        let rank = unsafe {
+            let key = String::from_utf8_unchecked(slice.to_vec());
+            ranks.get(&key).copied().unwrap_or(isize::MAX)
+        };
+        parts.push((i, rank));
     }
-    while parts.len() > 1 {
-        if let Some(min_idx) = (0..parts.len() - 1).min_by_key(|&i| parts[i][1]) {
-            let rank = get_rank(min_idx, 1, &parts);
-            if rank >= 0 {
-                parts[min_idx][1] = rank as usize;
-            }
-            if min_idx > 0 {
-                let rk = get_rank(min_idx - 1, 1, &parts);
-                if rk >= 0 {
-                    parts[min_idx - 1][1] = rk as usize;
-                }
-            }
-            parts.remove(min_idx + 1);
-        } else {
-            break;
+    parts.push((piece.len() - 1, isize::MAX));
+    parts.push((piece.len(), isize::MAX));
+
+    loop {
+        let min_idx = match parts[..parts.len() - 1]
+            .iter()
+            .enumerate()
+            .min_by_key(|&(_, &(_, rank))| rank)
+        {
+            Some((idx, &(_, rank))) if rank != isize::MAX => idx,
+            _ => break,
+        };
+        if min_idx > 0 {
+            parts[min_idx - 1].1 = pair_rank(piece, ranks, &parts, min_idx - 1);
         }
+        parts[min_idx].1 = pair_rank(piece, ranks, &parts, min_idx);
+        parts.remove(min_idx + 1);
     }
+
     (0..parts.len() - 1)
-        .map(|i| f(parts[i][0], parts[i + 1][0]))
+        .map(|i| f(parts[i].0, parts[i + 1].0))
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a `CoreBpe` whose vocabulary is every single byte (rank equal to
+    /// the byte value) plus one special token, with a pattern that matches a
+    /// whole single-line input as a single piece. No two-byte pair ever has a
+    /// rank, so `byte_pair_encode` never merges and every byte becomes its own
+    /// token — good enough to exercise encode/decode round-tripping without a
+    /// real `.tiktoken` vocabulary file.
+    fn test_bpe() -> CoreBpe {
+        let mut encoder: FxHashMap<String, isize> = FxHashMap::default();
+        for byte in 0u8..=255 {
+            unsafe {
+                let key = String::from_utf8_unchecked(vec![byte]);
+                encoder.insert(key, byte as isize);
+            }
+        }
+        let mut special_tokens_encoder: FxHashMap<String, isize> = FxHashMap::default();
+        special_tokens_encoder.insert("<|endoftext|>".to_string(), 100256);
+        CoreBpe::new(encoder, special_tokens_encoder, r".+".to_string()).unwrap()
+    }
+
+    #[test]
+    fn decode_round_trips_encoded_text() {
+        let bpe = test_bpe();
+        let text = "hello, world!";
+
+        let tokens = bpe.encode_ordinary_native(text);
+
+        assert_eq!(bpe.decode_native(&tokens).unwrap(), text);
+    }
+
+    #[test]
+    fn decode_bytes_reassembles_a_token_split_multibyte_character() {
+        let bpe = test_bpe();
+        let text = "日本語";
+
+        let tokens = bpe.encode_ordinary_native(text);
+        assert_eq!(tokens.len(), text.len()); // one token per byte, not per char
+
+        assert_eq!(bpe.decode_native(&tokens).unwrap(), text);
+    }
+
+    #[test]
+    fn decode_bytes_does_not_require_individual_tokens_to_be_valid_utf8() {
+        let bpe = test_bpe();
+        let tokens = bpe.encode_ordinary_native("日本語");
+
+        // the middle byte of a multibyte character is not valid UTF-8 on its own
+        let middle_token = &tokens[1..2];
+        let bytes = bpe.decode_bytes(middle_token).unwrap();
+        assert_eq!(bytes.len(), 1);
+        assert!(bpe.decode_native(middle_token).is_err());
+    }
+
+    #[test]
+    fn decode_bytes_errors_on_unknown_token_id() {
+        let bpe = test_bpe();
+
+        let err = bpe.decode_bytes(&[9999]).unwrap_err();
+
+        assert!(err.to_string().contains("9999"));
+    }
+
+    #[test]
+    fn encode_native_rejects_disallowed_special_token_with_position() {
+        let bpe = test_bpe();
+        let text = "hello <|endoftext|> world";
+
+        let err = bpe
+            .encode_native(text, &SpecialTokenAllowance::None)
+            .unwrap_err();
+
+        let message = err.to_string();
+        assert!(message.contains("<|endoftext|>"));
+        assert!(message.contains("position 6"));
+    }
+
+    #[test]
+    fn encode_native_allows_special_token_when_all_are_allowed() {
+        let bpe = test_bpe();
+        let text = "hello <|endoftext|> world";
+
+        let (tokens, _) = bpe.encode_native(text, &SpecialTokenAllowance::All).unwrap();
+
+        assert!(tokens.contains(&100256));
+    }
+
+    #[test]
+    fn encode_native_allows_special_token_in_explicit_allow_set() {
+        let bpe = test_bpe();
+        let mut allowed = HashSet::new();
+        allowed.insert("<|endoftext|>".to_string());
+        let text = "hi <|endoftext|>";
+
+        let (tokens, _) = bpe
+            .encode_native(text, &SpecialTokenAllowance::Some(allowed))
+            .unwrap();
+
+        assert!(tokens.contains(&100256));
+    }
+
+    #[test]
+    fn byte_pair_encode_returns_real_rank_zero() {
+        let mut ranks: FxHashMap<String, isize> = FxHashMap::default();
+        ranks.insert("a".to_string(), 5);
+        ranks.insert("b".to_string(), 6);
+        ranks.insert("ab".to_string(), 0);
+
+        assert_eq!(byte_pair_encode(b"ab", &ranks), vec![0]);
+    }
+
+    #[test]
+    fn byte_pair_encode_skips_pairs_missing_from_ranks() {
+        let mut ranks: FxHashMap<String, isize> = FxHashMap::default();
+        ranks.insert("a".to_string(), 10);
+        ranks.insert("b".to_string(), 10);
+        ranks.insert("c".to_string(), 10);
+        ranks.insert("bc".to_string(), 2);
+
+        assert_eq!(byte_pair_encode(b"abc", &ranks), vec![10, 2]);
+    }
+}
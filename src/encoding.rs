@@ -2,6 +2,7 @@ use anyhow::{anyhow, Error};
 use base64::Engine;
 use lazy_static::lazy_static;
 use maplit::hashmap;
+use rustc_hash::FxHashMap;
 use std::collections::HashMap;
 
 // enums
@@ -32,6 +33,7 @@ pub enum Dict {
     P50kBase,
     P50kEdit,
     R50kBase,
+    O200kBase,
 }
 
 impl Dict {
@@ -41,6 +43,7 @@ impl Dict {
             Self::P50kBase => String::from("p50k_base"),
             Self::P50kEdit => String::from("p50k_edit"),
             Self::R50kBase => String::from("r50k_base"),
+            Self::O200kBase => String::from("o200k_base"),
         }
     }
 
@@ -50,6 +53,7 @@ impl Dict {
             Self::P50kBase => include_bytes!("encodings/p50k_base.tiktoken"),
             Self::P50kEdit => include_bytes!("encodings/p50k_base.tiktoken"), // same to p50k_base
             Self::R50kBase => include_bytes!("encodings/r50k_base.tiktoken"),
+            Self::O200kBase => include_bytes!("encodings/o200k_base.tiktoken"),
         }
     }
 
@@ -58,6 +62,9 @@ impl Dict {
             Self::Cl100kBase => String::from(
                 r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+",
             ),
+            Self::O200kBase => String::from(
+                r"[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]*[\p{Ll}\p{Lm}\p{Lo}\p{M}]+(?i:'s|'t|'re|'ve|'m|'ll|'d)?|[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]+[\p{Ll}\p{Lm}\p{Lo}\p{M}]*(?i:'s|'t|'re|'ve|'m|'ll|'d)?|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n/]*|\s*[\r\n]+|\s+(?!\S)|\s+",
+            ),
             _ => String::from(
                 r"'s|'t|'re|'ve|'m|'ll|'d| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+(?!\S)|\s+",
             ),
@@ -69,11 +76,13 @@ impl Dict {
 pub enum ChatModelPrefix {
     Gpt3dot5,
     Gpt4,
+    Gpt4o,
 }
 
 impl ChatModelPrefix {
     pub fn to_string(&self) -> String {
         match self {
+            Self::Gpt4o => String::from("gpt-4o"),
             Self::Gpt4 => String::from("gpt-4"),
             Self::Gpt3dot5 => String::from("gpt-3.5"),
         }
@@ -84,6 +93,7 @@ impl ChatModelPrefix {
             _ if chat_model_name.starts_with(Self::Gpt3dot5.to_string().as_str()) => {
                 Ok(Self::Gpt3dot5)
             }
+            _ if chat_model_name.starts_with(Self::Gpt4o.to_string().as_str()) => Ok(Self::Gpt4o),
             _ if chat_model_name.starts_with(Self::Gpt4.to_string().as_str()) => Ok(Self::Gpt4),
             _ => Err(anyhow!("no prefix for model {}", chat_model_name)),
         }
@@ -97,6 +107,7 @@ lazy_static! {
         let mut map = HashMap::new();
         map.insert(ChatModelPrefix::Gpt3dot5, Dict::Cl100kBase);
         map.insert(ChatModelPrefix::Gpt4, Dict::Cl100kBase);
+        map.insert(ChatModelPrefix::Gpt4o, Dict::O200kBase);
         map
     };
 }
@@ -105,8 +116,8 @@ lazy_static! {
 
 pub struct Encoding {
     pub dict: Dict,
-    pub merging_ranks: HashMap<String, isize>,
-    pub special_tokens: HashMap<String, isize>,
+    pub merging_ranks: FxHashMap<String, isize>,
+    pub special_tokens: FxHashMap<String, isize>,
     pub explicit_vocab_size: isize,
 }
 
@@ -117,6 +128,7 @@ impl Encoding {
             Dict::P50kBase => p50k_base(),
             Dict::P50kEdit => p50k_edit(),
             Dict::R50kBase => r50k_base(),
+            Dict::O200kBase => o200k_base(),
         }
     }
 
@@ -135,13 +147,15 @@ impl Encoding {
 fn cl100k_base() -> Result<Encoding, Error> {
     let dict_data = Dict::Cl100kBase.get_file();
     let merging_ranks = parse_dict_data(dict_data)?;
-    let special_tokens = hashmap! {
+    let special_tokens: FxHashMap<String, isize> = hashmap! {
         SpecialToken::EndOfText.to_string() => 100257,
         SpecialToken::FimPrefix.to_string() => 100258,
         SpecialToken::FimMiddle.to_string() => 100259,
         SpecialToken::FimSuffix.to_string() => 100260,
         SpecialToken::EndOfPrompt.to_string() => 100276,
-    };
+    }
+    .into_iter()
+    .collect();
 
     Ok(Encoding {
         dict: Dict::Cl100kBase,
@@ -154,9 +168,11 @@ fn cl100k_base() -> Result<Encoding, Error> {
 fn p50k_base() -> Result<Encoding, Error> {
     let dict_data = Dict::P50kBase.get_file();
     let merging_ranks = parse_dict_data(dict_data)?;
-    let special_tokens = hashmap! {
+    let special_tokens: FxHashMap<String, isize> = hashmap! {
         SpecialToken::EndOfText.to_string() => 50256,
-    };
+    }
+    .into_iter()
+    .collect();
 
     Ok(Encoding {
         dict: Dict::P50kBase,
@@ -169,12 +185,14 @@ fn p50k_base() -> Result<Encoding, Error> {
 fn p50k_edit() -> Result<Encoding, Error> {
     let dict_data = Dict::P50kEdit.get_file();
     let merging_ranks = parse_dict_data(dict_data)?;
-    let special_tokens = hashmap! {
+    let special_tokens: FxHashMap<String, isize> = hashmap! {
         SpecialToken::EndOfText.to_string() => 50256,
         SpecialToken::FimPrefix.to_string() => 50281,
         SpecialToken::FimMiddle.to_string() => 50282,
         SpecialToken::FimSuffix.to_string() => 50283,
-    };
+    }
+    .into_iter()
+    .collect();
 
     Ok(Encoding {
         dict: Dict::P50kEdit,
@@ -187,9 +205,11 @@ fn p50k_edit() -> Result<Encoding, Error> {
 fn r50k_base() -> Result<Encoding, Error> {
     let dict_data = Dict::R50kBase.get_file();
     let merging_ranks = parse_dict_data(dict_data)?;
-    let special_tokens = hashmap! {
+    let special_tokens: FxHashMap<String, isize> = hashmap! {
         SpecialToken::EndOfText.to_string() => 50256,
-    };
+    }
+    .into_iter()
+    .collect();
 
     Ok(Encoding {
         dict: Dict::R50kBase,
@@ -199,8 +219,26 @@ fn r50k_base() -> Result<Encoding, Error> {
     })
 }
 
-fn parse_dict_data(contents: &[u8]) -> Result<HashMap<String, isize>, Error> {
-    let mut bpe_ranks = HashMap::new();
+fn o200k_base() -> Result<Encoding, Error> {
+    let dict_data = Dict::O200kBase.get_file();
+    let merging_ranks = parse_dict_data(dict_data)?;
+    let special_tokens: FxHashMap<String, isize> = hashmap! {
+        SpecialToken::EndOfText.to_string() => 199999,
+        SpecialToken::EndOfPrompt.to_string() => 200018,
+    }
+    .into_iter()
+    .collect();
+
+    Ok(Encoding {
+        dict: Dict::O200kBase,
+        merging_ranks,
+        special_tokens,
+        explicit_vocab_size: 0,
+    })
+}
+
+fn parse_dict_data(contents: &[u8]) -> Result<FxHashMap<String, isize>, Error> {
+    let mut bpe_ranks = FxHashMap::default();
     let engine = base64::engine::general_purpose::STANDARD;
     unsafe {
         let content_str = String::from_utf8_unchecked(Vec::from(contents));
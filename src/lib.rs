@@ -3,19 +3,57 @@ mod encoding;
 mod models;
 mod price;
 
+use anyhow::Error;
 use bpe::CoreBpe;
 use encoding::Encoding;
+use lazy_static::lazy_static;
+use models::Chat;
+use std::collections::HashMap;
 
-pub fn count_text(chat_model_name: &str, text: &str) -> isize {
-    let enc = Encoding::get_by_chat_model(chat_model_name).expect("get encoding failed");
-    let bpe = CoreBpe::new(
+pub use bpe::SpecialTokenAllowance;
+
+fn bpe_for_chat_model(chat_model_name: &str) -> Result<CoreBpe, Error> {
+    let enc = Encoding::get_by_chat_model(chat_model_name)?;
+    CoreBpe::new(
         enc.merging_ranks,
         enc.special_tokens,
         enc.dict.get_regex_pattern(),
     )
-    .expect("get bpe failed");
-    let tokens = bpe.encode_native(text).0;
-    tokens.len() as isize
+}
+
+pub fn count_text(chat_model_name: &str, text: &str) -> isize {
+    let bpe = bpe_for_chat_model(chat_model_name).expect("get bpe failed");
+    bpe.encode_ordinary_native(text).len() as isize
+}
+
+/// Encodes `text` as plain text, ignoring any special-token strings it may contain.
+pub fn encode(chat_model_name: &str, text: &str) -> Vec<isize> {
+    let bpe = bpe_for_chat_model(chat_model_name).expect("get bpe failed");
+    bpe.encode_ordinary_native(text)
+}
+
+/// Encodes `text`, recognizing and emitting special tokens covered by `allowed_special`.
+/// Any other special-token string found in `text` is rejected with an error identifying
+/// the offending substring and its position.
+pub fn encode_with_special_tokens(
+    chat_model_name: &str,
+    text: &str,
+    allowed_special: &SpecialTokenAllowance,
+) -> Result<Vec<isize>, Error> {
+    let bpe = bpe_for_chat_model(chat_model_name)?;
+    bpe.encode_native(text, allowed_special)
+        .map(|(tokens, _)| tokens)
+}
+
+pub fn decode(chat_model_name: &str, tokens: &[isize]) -> Result<String, Error> {
+    let bpe = bpe_for_chat_model(chat_model_name)?;
+    bpe.decode_native(tokens)
+}
+
+// like decode, but doesn't require the result to be valid UTF-8
+pub fn decode_bytes(chat_model_name: &str, tokens: &[isize]) -> Result<Vec<u8>, Error> {
+    let bpe = bpe_for_chat_model(chat_model_name)?;
+    bpe.decode_bytes(tokens)
 }
 
 pub struct ChatRequest {
@@ -30,29 +68,76 @@ pub struct ChatMessage {
 }
 
 pub fn count_request(request: &ChatRequest) -> isize {
-    let enc = Encoding::get_by_chat_model(&request.model).expect("get encoding failed");
-    let bpe = CoreBpe::new(
-        enc.merging_ranks,
-        enc.special_tokens,
-        enc.dict.get_regex_pattern(),
-    )
-    .expect("get bpe failed");
+    let bpe = bpe_for_chat_model(&request.model).expect("get bpe failed");
     let per_message: isize = 3;
     let per_name: isize = 1;
     let per_request: isize = 3;
     let mut count = per_request;
     for message in &request.messages {
         count += per_message;
-        count += bpe.encode_native(message.role.as_str()).0.len() as isize;
-        count += bpe.encode_native(message.content.as_str()).0.len() as isize;
+        count += bpe.encode_ordinary_native(message.role.as_str()).len() as isize;
+        count += bpe.encode_ordinary_native(message.content.as_str()).len() as isize;
         if let Some(name) = &message.name {
             count += per_name;
-            count += bpe.encode_native(name).0.len() as isize
+            count += bpe.encode_ordinary_native(name).len() as isize
         }
     }
     count
 }
 
+// context window
+
+lazy_static! {
+    pub static ref CHAT_MAX_CONTEXT_TOKENS: HashMap<Chat, isize> = {
+        let mut map = HashMap::new();
+        map.insert(Chat::Gpt3dot5TurboToken4k, 4096);
+        map.insert(Chat::Gpt3dot5TurboToken16k, 16384);
+        map.insert(Chat::Gpt4Token8k, 8192);
+        map.insert(Chat::Gpt4Token32k, 32768);
+        map.insert(Chat::Gpt4oToken128k, 128000);
+        map.insert(Chat::Gpt4oMiniToken128k, 128000);
+        map
+    };
+}
+
+impl Chat {
+    pub fn max_context_tokens(&self) -> isize {
+        *CHAT_MAX_CONTEXT_TOKENS.get(self).unwrap()
+    }
+}
+
+// returned by guard_request when a request wouldn't fit the context window
+#[derive(Debug)]
+pub struct ContextWindowExceeded {
+    pub overage: isize,
+}
+
+impl std::fmt::Display for ContextWindowExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "request exceeds context window by {} tokens", self.overage)
+    }
+}
+
+impl std::error::Error for ContextWindowExceeded {}
+
+pub fn remaining_tokens(request: &ChatRequest) -> isize {
+    let chat = Chat::get_by_name(&request.model).expect("get chat model failed");
+    chat.max_context_tokens() - count_request(request)
+}
+
+pub fn guard_request(request: &ChatRequest, max_output: isize) -> Result<isize, Error> {
+    let chat = Chat::get_by_name(&request.model)?;
+    let window = chat.max_context_tokens();
+    let total = count_request(request) + max_output;
+    if total > window {
+        return Err(ContextWindowExceeded {
+            overage: total - window,
+        }
+        .into());
+    }
+    Ok(window - total)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -62,4 +147,19 @@ mod tests {
         let result = count_text("gpt-4", "hello, openai");
         assert_eq!(result, 2);
     }
+
+    #[test]
+    fn gpt_4o_resolves_to_its_own_chat_model_instead_of_gpt_4() {
+        assert!(Chat::get_by_name("gpt-4o").unwrap() == Chat::Gpt4oToken128k);
+        assert!(Chat::get_by_name("gpt-4o-mini").unwrap() == Chat::Gpt4oMiniToken128k);
+    }
+
+    #[test]
+    fn gpt_4o_resolves_to_the_o200k_base_encoding_instead_of_cl100k() {
+        let enc = Encoding::get_by_chat_model("gpt-4o").unwrap();
+        assert_eq!(enc.dict.to_string(), "o200k_base");
+
+        let enc = Encoding::get_by_chat_model("gpt-4o-mini").unwrap();
+        assert_eq!(enc.dict.to_string(), "o200k_base");
+    }
 }
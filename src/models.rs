@@ -0,0 +1,52 @@
+use anyhow::{anyhow, Error};
+
+#[derive(Eq, PartialEq, Hash, Clone, Copy)]
+pub enum Chat {
+    Gpt3dot5TurboToken4k,
+    Gpt3dot5TurboToken16k,
+    Gpt4Token8k,
+    Gpt4Token32k,
+    Gpt4oToken128k,
+    Gpt4oMiniToken128k,
+}
+
+impl Chat {
+    pub fn to_string(&self) -> String {
+        match self {
+            Self::Gpt3dot5TurboToken4k => String::from("gpt-3.5-turbo"),
+            Self::Gpt3dot5TurboToken16k => String::from("gpt-3.5-turbo-16k"),
+            Self::Gpt4Token8k => String::from("gpt-4"),
+            Self::Gpt4Token32k => String::from("gpt-4-32k"),
+            Self::Gpt4oToken128k => String::from("gpt-4o"),
+            Self::Gpt4oMiniToken128k => String::from("gpt-4o-mini"),
+        }
+    }
+
+    pub fn get_by_name(chat_model_name: &str) -> Result<Self, Error> {
+        let name = chat_model_name.to_lowercase();
+        match () {
+            _ if name.starts_with(Self::Gpt4oMiniToken128k.to_string().as_str()) => {
+                Ok(Self::Gpt4oMiniToken128k)
+            }
+            _ if name.starts_with(Self::Gpt4oToken128k.to_string().as_str()) => {
+                Ok(Self::Gpt4oToken128k)
+            }
+            _ if name.starts_with(Self::Gpt4Token32k.to_string().as_str()) => {
+                Ok(Self::Gpt4Token32k)
+            }
+            _ if name.starts_with(Self::Gpt4Token8k.to_string().as_str()) => Ok(Self::Gpt4Token8k),
+            _ if name.starts_with(Self::Gpt3dot5TurboToken16k.to_string().as_str()) => {
+                Ok(Self::Gpt3dot5TurboToken16k)
+            }
+            _ if name.starts_with(Self::Gpt3dot5TurboToken4k.to_string().as_str()) => {
+                Ok(Self::Gpt3dot5TurboToken4k)
+            }
+            _ => Err(anyhow!("no chat model for name {}", chat_model_name)),
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Hash, Clone, Copy)]
+pub enum Embed {
+    TextEmbeddingAda002,
+}
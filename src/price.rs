@@ -13,6 +13,8 @@ lazy_static! {
         map.insert(Chat::Gpt3dot5TurboToken16k, dec!(0.003));
         map.insert(Chat::Gpt4Token8k, dec!(0.03));
         map.insert(Chat::Gpt4Token32k, dec!(0.06));
+        map.insert(Chat::Gpt4oToken128k, dec!(0.005));
+        map.insert(Chat::Gpt4oMiniToken128k, dec!(0.00015));
         map
     };
     pub static ref CHAT_PRICE_OUTPUT: HashMap<Chat, Decimal> = {
@@ -21,6 +23,8 @@ lazy_static! {
         map.insert(Chat::Gpt3dot5TurboToken16k, dec!(0.004));
         map.insert(Chat::Gpt4Token8k, dec!(0.06));
         map.insert(Chat::Gpt4Token32k, dec!(0.12));
+        map.insert(Chat::Gpt4oToken128k, dec!(0.015));
+        map.insert(Chat::Gpt4oMiniToken128k, dec!(0.0006));
         map
     };
     pub static ref EMBED_PRICE: HashMap<Embed, Decimal> = {